@@ -0,0 +1,178 @@
+//! A full-duplex encrypted connection that runs its handshake exactly once,
+//! then can be driven from two tasks at once via its split halves.
+
+use tokio::io::{split, AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+
+use crate::{cipher::CipherSuite, handshake, EncryptedReader, EncryptedWriter};
+
+/// An encrypted connection that has completed its handshake but has not
+/// yet been split into a read half and a write half.
+pub struct EncryptedStream<S> {
+    stream: S,
+    suite: CipherSuite,
+    write_key: [u8; 32],
+    write_nonce: [u8; 8],
+    read_key: [u8; 32],
+    read_nonce: [u8; 8],
+    /// Set once a reader/writer pair has been handed out by [`Self::split`]
+    /// or [`Self::into_split`]. Both always start encrypting/decrypting
+    /// from LE31 counter 0 under `write_key`/`read_key`, so handing out a
+    /// second pair would reuse the same key+nonce+counter the first pair
+    /// already used and break the AEAD.
+    already_split: bool,
+}
+
+impl<S> EncryptedStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Runs the X25519 handshake over `stream` once. Both sides must pass
+    /// the same `preferred_suite`; the handshake fails otherwise.
+    pub async fn new(
+        mut stream: S,
+        is_initiator: bool,
+        preferred_suite: CipherSuite,
+    ) -> std::io::Result<Self> {
+        let (keys, suite) =
+            handshake::handshake(&mut stream, is_initiator, preferred_suite).await?;
+
+        Ok(Self {
+            stream,
+            suite,
+            write_key: keys.write_key,
+            write_nonce: keys.write_nonce,
+            read_key: keys.read_key,
+            read_nonce: keys.read_nonce,
+            already_split: false,
+        })
+    }
+}
+
+impl<S> EncryptedStream<S>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin,
+{
+    /// Splits the connection into independent reader and writer halves
+    /// that can be driven concurrently from two tasks, carrying over the
+    /// already-derived per-direction keys and nonces so no second
+    /// handshake occurs. Dropping one half still lets the other flush and
+    /// shut down cleanly, since `tokio::io::split` keeps the underlying
+    /// stream alive until both halves are dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`Self::split`] or [`Self::into_split`] was already called
+    /// on this `EncryptedStream`, since a second pair of halves would reuse
+    /// the first pair's key, nonce, and LE31 counter.
+    pub fn into_split(mut self) -> (EncryptedReader<ReadHalf<S>>, EncryptedWriter<WriteHalf<S>>) {
+        assert!(
+            !self.already_split,
+            "EncryptedStream already split once; a second split would reuse the same key+nonce"
+        );
+        self.already_split = true;
+
+        let (read_half, write_half) = split(self.stream);
+
+        let reader =
+            EncryptedReader::from_parts(read_half, self.suite, &self.read_key, &self.read_nonce);
+        let writer = EncryptedWriter::from_parts(
+            write_half,
+            self.suite,
+            &self.write_key,
+            &self.write_nonce,
+        );
+
+        (reader, writer)
+    }
+
+    /// Like [`Self::into_split`], but borrows `self` instead of consuming
+    /// it, so the `EncryptedStream` itself can still be dropped or held
+    /// onto once the returned halves are dropped. Returns `None` if this
+    /// `EncryptedStream` has already been split (by this method or
+    /// [`Self::into_split`]) — unlike `tokio::io::split`, which can be
+    /// called repeatedly, the halves here carry a stream cipher whose key
+    /// and nonce can only safely be used by one pair of halves.
+    pub fn split(
+        &mut self,
+    ) -> Option<(EncryptedReader<ReadHalf<&mut S>>, EncryptedWriter<WriteHalf<&mut S>>)> {
+        if self.already_split {
+            return None;
+        }
+        self.already_split = true;
+
+        let (read_half, write_half) = split(&mut self.stream);
+
+        let reader =
+            EncryptedReader::from_parts(read_half, self.suite, &self.read_key, &self.read_nonce);
+        let writer = EncryptedWriter::from_parts(
+            write_half,
+            self.suite,
+            &self.write_key,
+            &self.write_nonce,
+        );
+
+        Some((reader, writer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn split_round_trips_without_consuming_the_stream() {
+        let (a, b) = tokio::io::duplex(4096);
+
+        let (stream_a, stream_b) = tokio::join!(
+            EncryptedStream::new(a, true, CipherSuite::ChaCha20Poly1305),
+            EncryptedStream::new(b, false, CipherSuite::ChaCha20Poly1305),
+        );
+        let mut stream_a = stream_a.unwrap();
+        let mut stream_b = stream_b.unwrap();
+
+        {
+            let (mut reader_b, _writer_b) = stream_b.split().unwrap();
+            let (_reader_a, mut writer_a) = stream_a.split().unwrap();
+
+            let write_fut = async {
+                writer_a.write_all(b"hello from a").await.unwrap();
+                writer_a.flush().await.unwrap();
+            };
+            let read_fut = async {
+                let mut buf = [0u8; 12];
+                reader_b.read_exact(&mut buf).await.unwrap();
+                buf
+            };
+            let (_, received) = tokio::join!(write_fut, read_fut);
+            assert_eq!(&received, b"hello from a");
+        }
+
+        // The split halves above borrowed `stream_a`/`stream_b` and have
+        // since been dropped, but splitting again would reuse the same
+        // key+nonce+LE31 counter the dropped halves already used, so it
+        // must be refused rather than silently handed out.
+        assert!(stream_a.split().is_none());
+        assert!(stream_b.split().is_none());
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "already split once")]
+    async fn into_split_after_split_panics_instead_of_reusing_the_key() {
+        let (a, b) = tokio::io::duplex(4096);
+
+        let (stream_a, stream_b) = tokio::join!(
+            EncryptedStream::new(a, true, CipherSuite::ChaCha20Poly1305),
+            EncryptedStream::new(b, false, CipherSuite::ChaCha20Poly1305),
+        );
+        let mut stream_a = stream_a.unwrap();
+        let _stream_b = stream_b.unwrap();
+
+        let halves = stream_a.split().unwrap();
+        drop(halves);
+
+        // Must panic instead of handing out a second pair of halves backed
+        // by the same key+nonce+LE31 counter as the dropped ones above.
+        let _ = stream_a.into_split();
+    }
+}
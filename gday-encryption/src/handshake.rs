@@ -0,0 +1,199 @@
+//! Establishes a shared key over an otherwise un-keyed connection, so that
+//! two hole-punched peers who only agree on a room password can still get
+//! an authenticated encryption key.
+
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret};
+
+use crate::cipher::CipherSuite;
+
+/// The keys and nonces derived from a completed handshake.
+///
+/// `write_*` must be used to encrypt data sent to the peer, and `read_*`
+/// must be used to decrypt data received from the peer. The two are
+/// derived independently so the LE31 nonce streams of the two directions
+/// never collide.
+pub(crate) struct HandshakeKeys {
+    pub write_key: [u8; 32],
+    pub write_nonce: [u8; 8],
+    pub read_key: [u8; 32],
+    pub read_nonce: [u8; 8],
+}
+
+/// Performs an X25519 Diffie-Hellman handshake over `stream`: exchanges an
+/// ephemeral public key and a random per-direction nonce with the peer
+/// (the initiator writes first, the responder reads first). Exactly 32 bytes of peer public key are read before any chunk
+/// framing is parsed, so this must run before the stream is wrapped in an
+/// [`crate::EncryptedReader`].
+///
+/// Also negotiates the [`CipherSuite`] the connection will use: the
+/// initiator advertises `preferred_suite` as the leading byte of its
+/// message, the responder requires its own `preferred_suite` to match
+/// before echoing it back, and the initiator in turn checks that the
+/// echoed byte still matches what it sent. An unrecognized or mismatched
+/// suite byte fails the handshake on whichever side notices, rather than
+/// silently producing garbage plaintext or letting either side dictate a
+/// suite the other never agreed to.
+pub(crate) async fn handshake<S>(
+    stream: &mut S,
+    is_initiator: bool,
+    preferred_suite: CipherSuite,
+) -> std::io::Result<(HandshakeKeys, CipherSuite)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let my_secret = EphemeralSecret::random_from_rng(OsRng);
+    let my_public = PublicKey::from(&my_secret);
+    let my_nonce: [u8; 8] = rand::random();
+
+    let mut my_message = [0; 41];
+    my_message[0] = preferred_suite.to_byte();
+    my_message[1..33].copy_from_slice(my_public.as_bytes());
+    my_message[33..41].copy_from_slice(&my_nonce);
+
+    let mut peer_message = [0; 41];
+    let agreed_suite;
+
+    if is_initiator {
+        stream.write_all(&my_message).await?;
+        stream.read_exact(&mut peer_message).await?;
+        agreed_suite = CipherSuite::from_byte(peer_message[0])?;
+        if agreed_suite != preferred_suite {
+            return Err(suite_mismatch_error(preferred_suite, agreed_suite));
+        }
+    } else {
+        stream.read_exact(&mut peer_message).await?;
+        let initiators_suite = CipherSuite::from_byte(peer_message[0])?;
+        if initiators_suite != preferred_suite {
+            return Err(suite_mismatch_error(preferred_suite, initiators_suite));
+        }
+        agreed_suite = preferred_suite;
+        my_message[0] = agreed_suite.to_byte();
+        stream.write_all(&my_message).await?;
+    }
+
+    let their_public = PublicKey::from(<[u8; 32]>::try_from(&peer_message[1..33]).unwrap());
+    let their_nonce: [u8; 8] = peer_message[33..41].try_into().unwrap();
+
+    let shared_secret = my_secret.diffie_hellman(&their_public);
+    let (write_key, read_key) = derive_directional_keys(&shared_secret, &my_public, &their_public);
+
+    Ok((
+        HandshakeKeys {
+            write_key,
+            write_nonce: my_nonce,
+            read_key,
+            read_nonce: their_nonce,
+        },
+        agreed_suite,
+    ))
+}
+
+fn suite_mismatch_error(wanted: CipherSuite, got: CipherSuite) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("cipher suite mismatch: this side requires {wanted:?}, peer sent {got:?}"),
+    )
+}
+
+/// Hashes the DH shared secret with a direction label to get two
+/// independent keys, one per direction. Both peers compute the same pair,
+/// and each works out which one is "mine to write with" by comparing
+/// public keys, so no prior notion of initiator/responder is needed.
+fn derive_directional_keys(
+    shared_secret: &SharedSecret,
+    my_public: &PublicKey,
+    their_public: &PublicKey,
+) -> ([u8; 32], [u8; 32]) {
+    let (lo, hi) = if my_public.as_bytes() < their_public.as_bytes() {
+        (my_public.as_bytes(), their_public.as_bytes())
+    } else {
+        (their_public.as_bytes(), my_public.as_bytes())
+    };
+
+    let lo_to_hi = hash_direction(shared_secret, lo, hi, b"lo->hi");
+    let hi_to_lo = hash_direction(shared_secret, lo, hi, b"hi->lo");
+
+    if my_public.as_bytes() == lo {
+        (lo_to_hi, hi_to_lo)
+    } else {
+        (hi_to_lo, lo_to_hi)
+    }
+}
+
+fn hash_direction(shared_secret: &SharedSecret, lo: &[u8], hi: &[u8], label: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(shared_secret.as_bytes());
+    hasher.update(lo);
+    hasher.update(hi);
+    hasher.update(label);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn initiator_and_responder_derive_crossed_keys() {
+        let (mut initiator_stream, mut responder_stream) = tokio::io::duplex(256);
+
+        let (initiator, responder) = tokio::join!(
+            handshake(&mut initiator_stream, true, CipherSuite::ChaCha20Poly1305),
+            handshake(&mut responder_stream, false, CipherSuite::ChaCha20Poly1305),
+        );
+        let (initiator_keys, initiator_suite) = initiator.unwrap();
+        let (responder_keys, responder_suite) = responder.unwrap();
+
+        assert_eq!(initiator_suite, CipherSuite::ChaCha20Poly1305);
+        assert_eq!(responder_suite, CipherSuite::ChaCha20Poly1305);
+
+        // What one side writes with, the other must read with, and vice versa.
+        assert_eq!(initiator_keys.write_key, responder_keys.read_key);
+        assert_eq!(initiator_keys.read_key, responder_keys.write_key);
+        assert_eq!(initiator_keys.write_nonce, responder_keys.read_nonce);
+        assert_eq!(initiator_keys.read_nonce, responder_keys.write_nonce);
+
+        // The two directions must not share a key, or their LE31 nonce
+        // streams could collide.
+        assert_ne!(initiator_keys.write_key, initiator_keys.read_key);
+    }
+
+    #[tokio::test]
+    async fn responder_rejects_suite_it_did_not_agree_to() {
+        let (mut initiator_stream, mut responder_stream) = tokio::io::duplex(256);
+
+        let mut fake_initiator_message = [0u8; 41];
+        fake_initiator_message[0] = CipherSuite::ChaCha20Poly1305.to_byte();
+        initiator_stream.write_all(&fake_initiator_message).await.unwrap();
+
+        let result = handshake(&mut responder_stream, false, CipherSuite::Aes256Gcm).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn initiator_rejects_a_suite_it_did_not_request() {
+        let (mut initiator_stream, mut fake_responder_stream) = tokio::io::duplex(256);
+
+        let initiator_fut = handshake(&mut initiator_stream, true, CipherSuite::ChaCha20Poly1305);
+
+        let fake_responder_fut = async {
+            let mut initiator_message = [0u8; 41];
+            fake_responder_stream
+                .read_exact(&mut initiator_message)
+                .await
+                .unwrap();
+
+            // Echo back a different suite than the initiator requested.
+            let mut reply = initiator_message;
+            reply[0] = CipherSuite::Aes256Gcm.to_byte();
+            fake_responder_stream.write_all(&reply).await.unwrap();
+        };
+
+        let (initiator_result, ()) = tokio::join!(initiator_fut, fake_responder_fut);
+        assert!(initiator_result.is_err());
+    }
+}
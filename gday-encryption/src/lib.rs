@@ -0,0 +1,60 @@
+#![allow(dead_code)]
+
+use bytes::BytesMut;
+
+mod cipher;
+mod encrypted_stream;
+mod handshake;
+mod reader;
+mod rekey;
+mod udp;
+mod writer;
+
+pub use cipher::CipherSuite;
+pub use encrypted_stream::EncryptedStream;
+pub use reader::EncryptedReader;
+pub use udp::EncryptedUdpSocket;
+pub use writer::EncryptedWriter;
+
+/// Maximum size in bytes of a single encrypted chunk's plaintext.
+pub const MAX_CHUNK_SIZE: usize = 1 << 16;
+
+/// A growable buffer with a read cursor, used to stage ciphertext/cleartext
+/// without re-allocating on every chunk.
+pub(crate) struct HelperBuf {
+    pub(crate) buf: BytesMut,
+    cursor: usize,
+}
+
+impl HelperBuf {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buf: BytesMut::with_capacity(capacity),
+            cursor: 0,
+        }
+    }
+
+    /// The unread bytes currently in the buffer.
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.buf[self.cursor..]
+    }
+
+    /// Marks `amount` bytes at the front of [`Self::data()`] as read.
+    pub(crate) fn advance_cursor(&mut self, amount: usize) {
+        self.cursor += amount;
+    }
+
+    /// Remaining capacity not yet occupied by written bytes.
+    pub(crate) fn spare_capacity_len(&self) -> usize {
+        self.buf.capacity() - self.buf.len()
+    }
+
+    /// Shifts any unread bytes to the front of the buffer, reclaiming the
+    /// space already consumed by the read cursor.
+    pub(crate) fn wrap(&mut self) {
+        let remaining = self.buf.split_off(self.cursor);
+        self.buf.clear();
+        self.buf.unsplit(remaining);
+        self.cursor = 0;
+    }
+}
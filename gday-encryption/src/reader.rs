@@ -1,48 +1,93 @@
 #![allow(dead_code)]
-use chacha20poly1305::{aead::stream::DecryptorLE31, ChaCha20Poly1305};
 use pin_project::pin_project;
 use std::{
     io::ErrorKind,
     pin::Pin,
     task::{ready, Context, Poll},
 };
-use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, ReadBuf};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
 
-use crate::{HelperBuf, MAX_CHUNK_SIZE};
+use crate::{
+    cipher::{CipherSuite, StreamDecryptor},
+    handshake,
+    rekey::{derive_rekeyed_key, derive_rekeyed_nonce, LENGTH_MASK, REKEY_FLAG, SALT_LEN},
+    HelperBuf, MAX_CHUNK_SIZE,
+};
 
 pub trait AsyncReadable: AsyncRead + Send + Unpin {}
 impl<T: AsyncRead + Send + Unpin> AsyncReadable for T {}
 
-fn peek_cipher_chunk(buf: &mut HelperBuf) -> Option<&[u8]> {
-    if let Some(len) = buf.data().get(0..4) {
-        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
-        buf.data().get(4..4 + len)
-    } else {
-        None
-    }
+/// A full chunk header, and the body it announces the length of: either a
+/// ciphertext, or (if `is_rekey`) a raw salt for [`crate::rekey`].
+struct CipherChunk<'a> {
+    is_rekey: bool,
+    body: &'a [u8],
+}
+
+fn peek_cipher_chunk(buf: &mut HelperBuf) -> Option<CipherChunk<'_>> {
+    let header = buf.data().get(0..4)?;
+    let header = u32::from_be_bytes(header.try_into().unwrap());
+    let is_rekey = header & REKEY_FLAG != 0;
+    let len = (header & LENGTH_MASK) as usize;
+    let body = buf.data().get(4..4 + len)?;
+    Some(CipherChunk { is_rekey, body })
 }
 
 #[pin_project]
 pub struct EncryptedReader<T: AsyncReadable> {
     #[pin]
     reader: T,
-    decryptor: DecryptorLE31<ChaCha20Poly1305>,
+    decryptor: StreamDecryptor,
+    suite: CipherSuite,
+    /// The key currently backing `decryptor`, kept around so the next
+    /// rekey can be hashed from it.
+    current_key: [u8; 32],
     cleartext: HelperBuf,
     ciphertext: HelperBuf,
 }
 
 impl<T: AsyncReadable> EncryptedReader<T> {
-    pub async fn new(mut reader: T, shared_key: [u8; 32]) -> std::io::Result<Self> {
-        let mut nonce = [0; 8];
-        reader.read_exact(&mut nonce).await?;
+    /// Runs an X25519 handshake over `reader` to derive a decryption key and
+    /// nonce shared with no prior out-of-band secret, then wraps `reader`.
+    /// `is_initiator` and `preferred_suite` are only consulted to negotiate
+    /// which [`CipherSuite`] secures the connection; the responder always
+    /// ends up using whatever suite the two sides agree on.
+    pub async fn new(
+        mut reader: T,
+        is_initiator: bool,
+        preferred_suite: CipherSuite,
+    ) -> std::io::Result<Self>
+    where
+        T: AsyncWrite,
+    {
+        let (keys, suite) =
+            handshake::handshake(&mut reader, is_initiator, preferred_suite).await?;
+
+        Ok(Self::from_parts(
+            reader,
+            suite,
+            &keys.read_key,
+            &keys.read_nonce,
+        ))
+    }
 
-        let decryptor = DecryptorLE31::new(&shared_key.into(), &nonce.into());
-        Ok(Self {
+    /// Wraps `reader` with a decryption key and nonce that were already
+    /// derived elsewhere (e.g. by [`crate::EncryptedStream`]'s single
+    /// handshake), instead of running a handshake of its own.
+    pub(crate) fn from_parts(
+        reader: T,
+        suite: CipherSuite,
+        key: &[u8; 32],
+        nonce: &[u8; 8],
+    ) -> Self {
+        Self {
             reader,
-            decryptor,
+            decryptor: StreamDecryptor::new(suite, key, nonce),
+            suite,
+            current_key: *key,
             cleartext: HelperBuf::with_capacity(MAX_CHUNK_SIZE),
             ciphertext: HelperBuf::with_capacity(MAX_CHUNK_SIZE * 2),
-        })
+        }
     }
 
     fn inner_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
@@ -64,8 +109,22 @@ impl<T: AsyncReadable> EncryptedReader<T> {
 
     fn decrypt_all_full_chunks(self: Pin<&mut Self>) -> std::io::Result<()> {
         let this = self.project();
-        while let Some(msg) = peek_cipher_chunk(this.ciphertext) {
-            let msg_len = msg.len();
+        while let Some(chunk) = peek_cipher_chunk(this.ciphertext) {
+            if chunk.is_rekey {
+                let salt: [u8; SALT_LEN] = chunk.body.try_into().map_err(|_| {
+                    std::io::Error::new(ErrorKind::InvalidData, "Malformed rekey chunk")
+                })?;
+
+                let new_key = derive_rekeyed_key(this.current_key, &salt);
+                let new_nonce = derive_rekeyed_nonce(this.current_key, &salt);
+                *this.decryptor = StreamDecryptor::new(*this.suite, &new_key, &new_nonce);
+                *this.current_key = new_key;
+
+                this.ciphertext.advance_cursor(chunk.body.len() + 4);
+                continue;
+            }
+
+            let msg_len = chunk.body.len();
             if this.cleartext.spare_capacity_len() < msg_len {
                 break;
             }
@@ -73,12 +132,12 @@ impl<T: AsyncReadable> EncryptedReader<T> {
             let cleartext_len = this.cleartext.buf.len();
             let mut decryption_space = this.cleartext.buf.split_off(cleartext_len);
 
-            decryption_space.extend_from_slice(msg);
+            decryption_space.extend_from_slice(chunk.body);
 
             this.ciphertext.advance_cursor(msg_len + 4);
 
             this.decryptor
-                .decrypt_next_in_place(&[], &mut decryption_space)
+                .decrypt_next_in_place(&mut decryption_space)
                 .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "Decryption error"))?;
 
             this.cleartext.buf.unsplit(decryption_space);
@@ -138,7 +197,6 @@ impl<T: AsyncReadable> AsyncRead for EncryptedReader<T> {
 
         let is_eof = ready!(self.as_mut().read_if_necessary(cx, Some(buf.remaining()))?);
         if is_eof {
-            println!("hao");
             return Poll::Ready(Ok(()));
         }
 
@@ -0,0 +1,40 @@
+//! In-band rekeying so a long-lived connection isn't bound by
+//! [`chacha20poly1305::aead::stream::DecryptorLE31`]'s 31-bit message
+//! counter, and gets periodic forward secrecy without tearing down the
+//! underlying connection.
+//!
+//! A rekey is signalled by setting the top bit of the usual 4-byte BE
+//! chunk-length header; the remaining 31 bits then give the length of an
+//! unencrypted salt, instead of a ciphertext. Both sides hash the current
+//! key with that salt to get the next one, so no extra round trip is
+//! needed.
+
+use sha2::{Digest, Sha256};
+
+/// Marks a chunk header as a rekey control chunk rather than a length.
+pub(crate) const REKEY_FLAG: u32 = 1 << 31;
+/// Masks the header down to the 31-bit chunk/salt length.
+pub(crate) const LENGTH_MASK: u32 = !REKEY_FLAG;
+
+/// Number of bytes of random salt carried by a rekey control chunk.
+pub(crate) const SALT_LEN: usize = 8;
+
+/// Default number of data chunks an [`crate::EncryptedWriter`] sends before
+/// rekeying, well short of the LE31 stream's 31-bit counter limit.
+pub(crate) const DEFAULT_REKEY_INTERVAL_CHUNKS: u32 = 1 << 20;
+
+pub(crate) fn derive_rekeyed_key(current_key: &[u8; 32], salt: &[u8; SALT_LEN]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(current_key);
+    hasher.update(salt);
+    hasher.update(b"rekey-key");
+    hasher.finalize().into()
+}
+
+pub(crate) fn derive_rekeyed_nonce(current_key: &[u8; 32], salt: &[u8; SALT_LEN]) -> [u8; 8] {
+    let mut hasher = Sha256::new();
+    hasher.update(current_key);
+    hasher.update(salt);
+    hasher.update(b"rekey-nonce");
+    hasher.finalize()[0..8].try_into().unwrap()
+}
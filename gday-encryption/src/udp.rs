@@ -0,0 +1,225 @@
+//! An encrypted UDP datagram channel for the actual hole-punched
+//! peer-to-peer link, as a sibling to the stream-oriented
+//! [`crate::EncryptedStream`]: datagrams can be lost or reordered, so each
+//! one is encrypted independently instead of being threaded through a
+//! sequential LE31 stream cipher.
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305,
+};
+use std::{
+    io::ErrorKind,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use tokio::net::UdpSocket;
+
+const NONCE_LEN: usize = 12;
+const SEQ_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+
+/// Wraps a [`UdpSocket`], encrypting each datagram independently under a
+/// fresh random nonce so packet loss and reordering can't desynchronize
+/// any stream cipher state. The 32-byte key is supplied by the caller:
+/// this type runs no handshake of its own, and [`crate::EncryptedStream`]
+/// does not expose the keys its handshake derives.
+pub struct EncryptedUdpSocket {
+    socket: UdpSocket,
+    cipher: ChaCha20Poly1305,
+    next_seq: AtomicU64,
+    replay_window: Mutex<ReplayWindow>,
+}
+
+impl EncryptedUdpSocket {
+    pub fn new(socket: UdpSocket, key: [u8; 32]) -> Self {
+        Self {
+            socket,
+            cipher: ChaCha20Poly1305::new(&key.into()),
+            next_seq: AtomicU64::new(0),
+            replay_window: Mutex::new(ReplayWindow::new()),
+        }
+    }
+
+    /// Encrypts `payload` under a fresh random nonce and an explicit
+    /// sequence number (included as AAD so the receiver can reject
+    /// replays), then sends `nonce || seq || ciphertext+tag` to `target`.
+    pub async fn send_to(&self, payload: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed).to_be_bytes();
+
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce.into(),
+                Payload {
+                    msg: payload,
+                    aad: &seq,
+                },
+            )
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "Encryption error"))?;
+
+        let mut datagram = Vec::with_capacity(NONCE_LEN + SEQ_LEN + ciphertext.len());
+        datagram.extend_from_slice(&nonce);
+        datagram.extend_from_slice(&seq);
+        datagram.extend_from_slice(&ciphertext);
+
+        self.socket.send_to(&datagram, target).await
+    }
+
+    /// Receives one datagram, splits off its nonce and sequence number,
+    /// and decrypts the rest. Returns `Ok(None)` (instead of an error) if
+    /// the datagram fails authentication or its sequence number falls
+    /// outside the replay window, since a spoofed or duplicated packet is
+    /// expected background noise on an unauthenticated transport, not a
+    /// fatal I/O error.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<Option<(usize, SocketAddr)>> {
+        let mut datagram = vec![0; buf.len() + NONCE_LEN + SEQ_LEN + TAG_LEN];
+        let (len, from) = self.socket.recv_from(&mut datagram).await?;
+        datagram.truncate(len);
+
+        if datagram.len() < NONCE_LEN + SEQ_LEN {
+            return Ok(None);
+        }
+
+        let nonce = &datagram[0..NONCE_LEN];
+        let seq_bytes = &datagram[NONCE_LEN..NONCE_LEN + SEQ_LEN];
+        let ciphertext = &datagram[NONCE_LEN + SEQ_LEN..];
+        let seq = u64::from_be_bytes(seq_bytes.try_into().unwrap());
+
+        let Ok(plaintext) = self.cipher.decrypt(
+            nonce.into(),
+            Payload {
+                msg: ciphertext,
+                aad: seq_bytes,
+            },
+        ) else {
+            return Ok(None);
+        };
+
+        if !self.replay_window.lock().unwrap().accept(seq) {
+            return Ok(None);
+        }
+
+        if plaintext.len() > buf.len() {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "decrypted datagram larger than buffer",
+            ));
+        }
+        buf[..plaintext.len()].copy_from_slice(&plaintext);
+
+        Ok(Some((plaintext.len(), from)))
+    }
+}
+
+/// A sliding bitmask window that rejects sequence numbers already seen, or
+/// so old they've fallen off the back of the window.
+struct ReplayWindow {
+    highest_seen: u64,
+    seen_mask: u64,
+}
+
+impl ReplayWindow {
+    fn new() -> Self {
+        Self {
+            highest_seen: 0,
+            seen_mask: 0,
+        }
+    }
+
+    /// Returns `true` if `seq` is new and should be accepted, recording it
+    /// as seen in the process.
+    fn accept(&mut self, seq: u64) -> bool {
+        if seq > self.highest_seen {
+            let shift = seq - self.highest_seen;
+            self.seen_mask = if shift >= 64 { 0 } else { self.seen_mask << shift };
+            self.seen_mask |= 1;
+            self.highest_seen = seq;
+            return true;
+        }
+
+        let age = self.highest_seen - seq;
+        if age >= 64 {
+            return false;
+        }
+
+        let bit = 1 << age;
+        if self.seen_mask & bit != 0 {
+            false
+        } else {
+            self.seen_mask |= bit;
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn connected_pair() -> (EncryptedUdpSocket, EncryptedUdpSocket) {
+        let key = [7u8; 32];
+        let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        a.connect(b.local_addr().unwrap()).await.unwrap();
+        b.connect(a.local_addr().unwrap()).await.unwrap();
+        (
+            EncryptedUdpSocket::new(a, key),
+            EncryptedUdpSocket::new(b, key),
+        )
+    }
+
+    #[tokio::test]
+    async fn round_trips_a_datagram() {
+        let (sender, receiver) = connected_pair().await;
+        let target = receiver.socket.local_addr().unwrap();
+
+        sender.send_to(b"hello over udp", target).await.unwrap();
+
+        let mut buf = [0u8; 64];
+        let (len, _from) = receiver.recv_from(&mut buf).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"hello over udp");
+    }
+
+    #[tokio::test]
+    async fn rejects_a_replayed_datagram() {
+        let (sender, receiver) = connected_pair().await;
+        let target = receiver.socket.local_addr().unwrap();
+
+        // Encrypt one datagram by hand (instead of via send_to, which
+        // would pick a fresh nonce/seq each call) so the exact same wire
+        // bytes can be sent twice, simulating an attacker capturing and
+        // replaying a real packet.
+        let nonce = [1u8; NONCE_LEN];
+        let seq = 0u64.to_be_bytes();
+        let ciphertext = sender
+            .cipher
+            .encrypt(
+                &nonce.into(),
+                Payload {
+                    msg: b"only once",
+                    aad: &seq,
+                },
+            )
+            .unwrap();
+        let mut datagram = Vec::with_capacity(NONCE_LEN + SEQ_LEN + ciphertext.len());
+        datagram.extend_from_slice(&nonce);
+        datagram.extend_from_slice(&seq);
+        datagram.extend_from_slice(&ciphertext);
+
+        sender.socket.send_to(&datagram, target).await.unwrap();
+        sender.socket.send_to(&datagram, target).await.unwrap();
+
+        let mut buf = [0u8; 64];
+        let (len, _from) = receiver.recv_from(&mut buf).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"only once");
+
+        // The identical datagram, resent, must be rejected end-to-end
+        // through recv_from rather than merely at the ReplayWindow level.
+        assert!(receiver.recv_from(&mut buf).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,231 @@
+#![allow(dead_code)]
+use pin_project::pin_project;
+use std::{
+    io::ErrorKind,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    cipher::{CipherSuite, StreamEncryptor},
+    handshake,
+    rekey::{derive_rekeyed_key, derive_rekeyed_nonce, DEFAULT_REKEY_INTERVAL_CHUNKS, REKEY_FLAG},
+    MAX_CHUNK_SIZE,
+};
+
+pub trait AsyncWritable: AsyncWrite + Send + Unpin {}
+impl<T: AsyncWrite + Send + Unpin> AsyncWritable for T {}
+
+#[pin_project]
+pub struct EncryptedWriter<T: AsyncWritable> {
+    #[pin]
+    writer: T,
+    encryptor: StreamEncryptor,
+    suite: CipherSuite,
+    /// The key currently backing `encryptor`, kept around so the next
+    /// rekey can be hashed from it.
+    current_key: [u8; 32],
+    chunks_since_rekey: u32,
+    rekey_interval_chunks: u32,
+    /// The current chunk's 4-byte length prefix plus ciphertext (and any
+    /// rekey control chunk emitted just ahead of it), still being flushed
+    /// to `writer`.
+    pending: Vec<u8>,
+    pending_written: usize,
+}
+
+impl<T: AsyncWritable> EncryptedWriter<T> {
+    /// Runs an X25519 handshake over `writer` to derive an encryption key
+    /// and nonce shared with no prior out-of-band secret, then wraps
+    /// `writer`. `is_initiator` and `preferred_suite` are only consulted to
+    /// negotiate which [`CipherSuite`] secures the connection. Rekeys every
+    /// [`DEFAULT_REKEY_INTERVAL_CHUNKS`]; use [`Self::from_parts_with_rekey_interval`]
+    /// to override that.
+    pub async fn new(
+        mut writer: T,
+        is_initiator: bool,
+        preferred_suite: CipherSuite,
+    ) -> std::io::Result<Self>
+    where
+        T: AsyncRead + Unpin,
+    {
+        let (keys, suite) =
+            handshake::handshake(&mut writer, is_initiator, preferred_suite).await?;
+
+        Ok(Self::from_parts(
+            writer,
+            suite,
+            &keys.write_key,
+            &keys.write_nonce,
+        ))
+    }
+
+    /// Wraps `writer` with an encryption key and nonce that were already
+    /// derived elsewhere (e.g. by [`crate::EncryptedStream`]'s single
+    /// handshake), instead of running a handshake of its own.
+    pub(crate) fn from_parts(
+        writer: T,
+        suite: CipherSuite,
+        key: &[u8; 32],
+        nonce: &[u8; 8],
+    ) -> Self {
+        Self::from_parts_with_rekey_interval(
+            writer,
+            suite,
+            key,
+            nonce,
+            DEFAULT_REKEY_INTERVAL_CHUNKS,
+        )
+    }
+
+    /// Like [`Self::from_parts`], but rekeys every `rekey_interval_chunks`
+    /// data chunks instead of the default.
+    pub(crate) fn from_parts_with_rekey_interval(
+        writer: T,
+        suite: CipherSuite,
+        key: &[u8; 32],
+        nonce: &[u8; 8],
+        rekey_interval_chunks: u32,
+    ) -> Self {
+        Self {
+            writer,
+            encryptor: StreamEncryptor::new(suite, key, nonce),
+            suite,
+            current_key: *key,
+            chunks_since_rekey: 0,
+            rekey_interval_chunks,
+            pending: Vec::new(),
+            pending_written: 0,
+        }
+    }
+
+    /// Flushes any bytes of the current chunk that haven't made it to
+    /// `writer` yet.
+    fn flush_pending(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let mut this = self.project();
+        while *this.pending_written < this.pending.len() {
+            let bytes_written = ready!(this
+                .writer
+                .as_mut()
+                .poll_write(cx, &this.pending[*this.pending_written..]))?;
+            *this.pending_written += bytes_written;
+        }
+        this.pending.clear();
+        *this.pending_written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWritable> AsyncWrite for EncryptedWriter<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(self.as_mut().flush_pending(cx))?;
+
+        let this = self.project();
+
+        if *this.chunks_since_rekey >= *this.rekey_interval_chunks {
+            let salt: [u8; crate::rekey::SALT_LEN] = rand::random();
+            let new_key = derive_rekeyed_key(this.current_key, &salt);
+            let new_nonce = derive_rekeyed_nonce(this.current_key, &salt);
+
+            this.pending
+                .extend_from_slice(&(REKEY_FLAG | salt.len() as u32).to_be_bytes());
+            this.pending.extend_from_slice(&salt);
+
+            *this.encryptor = StreamEncryptor::new(*this.suite, &new_key, &new_nonce);
+            *this.current_key = new_key;
+            *this.chunks_since_rekey = 0;
+        }
+
+        let chunk_len = std::cmp::min(buf.len(), MAX_CHUNK_SIZE);
+
+        let mut ciphertext = buf[..chunk_len].to_vec();
+        this.encryptor
+            .encrypt_next_in_place(&mut ciphertext)
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "Encryption error"))?;
+
+        this.pending
+            .extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+        this.pending.extend_from_slice(&ciphertext);
+        *this.chunks_since_rekey += 1;
+
+        Poll::Ready(Ok(chunk_len))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.as_mut().flush_pending(cx))?;
+        let this = self.project();
+        this.writer.poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        let this = self.project();
+        this.writer.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EncryptedReader;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Sends chunks across multiple rekey boundaries and checks the reader
+    /// keeps decrypting correctly, i.e. it processes the rekey control
+    /// chunks in order and re-initializes its cipher in lockstep with the
+    /// writer.
+    #[tokio::test]
+    async fn rekeys_mid_stream_and_reader_keeps_decrypting() {
+        let (a, b) = tokio::io::duplex(1 << 16);
+
+        let suite = CipherSuite::ChaCha20Poly1305;
+        let key = [3u8; 32];
+        let nonce = [9u8; 8];
+
+        let mut writer = EncryptedWriter::from_parts_with_rekey_interval(a, suite, &key, &nonce, 2);
+        let mut reader = EncryptedReader::from_parts(b, suite, &key, &nonce);
+
+        for i in 0..5u8 {
+            let chunk = vec![i; 10];
+            writer.write_all(&chunk).await.unwrap();
+            writer.flush().await.unwrap();
+
+            let mut buf = vec![0u8; 10];
+            reader.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, chunk);
+        }
+    }
+
+    /// AES-256-GCM is the whole point of cipher negotiation, but until now
+    /// nothing actually ran a chunk through `StreamEncryptor::Aes256Gcm`/
+    /// `StreamDecryptor::Aes256Gcm` — only `handshake.rs` checked the
+    /// negotiated suite byte. Round-trip several chunks under it, across a
+    /// rekey boundary, the same way `rekeys_mid_stream_and_reader_keeps_decrypting`
+    /// does for ChaCha20Poly1305.
+    #[tokio::test]
+    async fn rekeys_mid_stream_under_aes_256_gcm() {
+        let (a, b) = tokio::io::duplex(1 << 16);
+
+        let suite = CipherSuite::Aes256Gcm;
+        let key = [5u8; 32];
+        let nonce = [11u8; 8];
+
+        let mut writer = EncryptedWriter::from_parts_with_rekey_interval(a, suite, &key, &nonce, 2);
+        let mut reader = EncryptedReader::from_parts(b, suite, &key, &nonce);
+
+        for i in 0..5u8 {
+            let chunk = vec![i; 10];
+            writer.write_all(&chunk).await.unwrap();
+            writer.flush().await.unwrap();
+
+            let mut buf = vec![0u8; 10];
+            reader.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, chunk);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{Contact, FullContact, RoomId};
+use crate::{FullContact, RoomId};
 use rand::seq::SliceRandom;
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -11,16 +11,24 @@ use super::ServerError;
 #[derive(Default)]
 struct Client {
     contact: FullContact,
-    waiting: Option<oneshot::Sender<(Contact, FullContact)>>,
+    waiting: Option<oneshot::Sender<Vec<FullContact>>>,
+}
+
+/// A room in progress: some of its `expected_size` slots may still be
+/// vacant (`None`), either because the corresponding client hasn't joined
+/// yet, or because it disconnected before the room filled.
+#[derive(Default)]
+struct Room {
+    clients: Vec<Option<Client>>,
+    expected_size: u8,
 }
 
 #[derive(Clone, Default)]
 pub struct State {
-    /// Maps room_id to clients
-    rooms: Arc<Mutex<HashMap<RoomId, [Client; 2]>>>,
+    /// Maps room_id to its room
+    rooms: Arc<Mutex<HashMap<RoomId, Room>>>,
 }
 
-
 fn generate_room_id() -> RoomId {
     let mut rng = rand::thread_rng();
     let characters = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
@@ -33,7 +41,10 @@ fn generate_room_id() -> RoomId {
 }
 
 impl State {
-    pub fn create_room(&mut self) -> RoomId {
+    /// Creates a room for `expected_size` total participants, and
+    /// immediately occupies slot 0 with the creator. Returns the new
+    /// room's id.
+    pub fn create_room(&mut self, expected_size: u8) -> RoomId {
         let mut rooms = self.rooms.lock().unwrap();
 
         let mut room_id = generate_room_id();
@@ -41,23 +52,58 @@ impl State {
             room_id = generate_room_id();
         }
 
-        rooms.insert(room_id, [Client::default(), Client::default()]);
+        rooms.insert(
+            room_id,
+            Room {
+                clients: vec![Some(Client::default())],
+                expected_size,
+            },
+        );
         self.room_timeout(room_id);
 
         room_id
     }
 
+    /// Joins an existing room, filling the first vacant slot (reusing one
+    /// left behind by a client that disconnected early) or adding a new
+    /// one. Returns the joining client's index within the room.
+    pub fn join_room(&mut self, room_id: RoomId) -> Result<usize, ServerError> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = rooms
+            .get_mut(&room_id)
+            .ok_or(ServerError::NoSuchRoomExists)?;
+
+        if let Some(index) = room.clients.iter().position(Option::is_none) {
+            room.clients[index] = Some(Client::default());
+            return Ok(index);
+        }
+
+        if room.clients.len() >= usize::from(room.expected_size) {
+            return Err(ServerError::RoomFull);
+        }
+
+        room.clients.push(Some(Client::default()));
+        Ok(room.clients.len() - 1)
+    }
+
     pub fn update_client(
         &mut self,
         room_id: RoomId,
-        is_creator: bool,
+        client_index: usize,
         endpoint: SocketAddr,
         public: bool,
     ) -> Result<(), ServerError> {
         let mut rooms = self.rooms.lock().unwrap();
-        let room = rooms.get_mut(&room_id).ok_or(ServerError::NoSuchRoomExists)?;
-        let contact = &mut room[usize::from(is_creator)].contact;
+        let room = rooms
+            .get_mut(&room_id)
+            .ok_or(ServerError::NoSuchRoomExists)?;
+        let client = room
+            .clients
+            .get_mut(client_index)
+            .and_then(Option::as_mut)
+            .ok_or(ServerError::NoSuchRoomExists)?;
 
+        let contact = &mut client.contact;
         let contact = if public {
             &mut contact.public
         } else {
@@ -76,42 +122,69 @@ impl State {
         Ok(())
     }
 
+    /// Removes a client that disconnected before the room filled, vacating
+    /// its slot so a later joiner can take it without disturbing the
+    /// indices already handed out to everyone else.
+    pub fn disconnect_client(&mut self, room_id: RoomId, client_index: usize) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(room) = rooms.get_mut(&room_id) {
+            if let Some(slot) = room.clients.get_mut(client_index) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Marks a client as done sending its contacts. Once every slot in the
+    /// room is filled and every client in it has called this, every
+    /// client's full contact info is fanned out to all the others.
     pub fn set_client_done(
         &mut self,
         room_id: RoomId,
-        is_creator: bool,
-    ) -> Result<oneshot::Receiver<(Contact, FullContact)>, ServerError> {
+        client_index: usize,
+    ) -> Result<oneshot::Receiver<Vec<FullContact>>, ServerError> {
         let mut rooms = self.rooms.lock().unwrap();
-        let room = rooms.get_mut(&room_id).ok_or(ServerError::NoSuchRoomExists)?;
+        let room = rooms
+            .get_mut(&room_id)
+            .ok_or(ServerError::NoSuchRoomExists)?;
 
-        let client_i = usize::from(is_creator);
-        let peer_i = usize::from(!is_creator);
+        let (tx, rx) = oneshot::channel();
+        room.clients
+            .get_mut(client_index)
+            .and_then(Option::as_mut)
+            .ok_or(ServerError::NoSuchRoomExists)?
+            .waiting = Some(tx);
 
-        let client = &mut room[client_i];
+        let room_full = room.clients.len() == usize::from(room.expected_size);
+        let all_done = room_full
+            && room
+                .clients
+                .iter()
+                .all(|client| matches!(client, Some(client) if client.waiting.is_some()));
 
-        let (tx, rx) = oneshot::channel();
-        client.waiting = Some(tx);
-
-        let peer = &room[peer_i];
-
-        if peer.waiting.is_some() {
-            let client_info = room[client_i].contact;
-            let peer_info = peer.contact;
-
-            let client = &mut room[client_i];
-            client
-                .waiting
-                .take()
-                .unwrap()
-                .send((client_info.public, peer_info))
-                .unwrap();
-
-            let peer = &mut room[peer_i];
-            peer.waiting
-                .take()
-                .unwrap()
-                .send((peer_info.public, client_info))
-                .unwrap();
+        if all_done {
+            let contacts: Vec<FullContact> = room
+                .clients
+                .iter()
+                .map(|client| client.as_ref().unwrap().contact.clone())
+                .collect();
+
+            for (i, client) in room.clients.iter_mut().enumerate() {
+                let client = client.as_mut().unwrap();
+                let peer_contacts = contacts
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .map(|(_, contact)| contact.clone())
+                    .collect();
+
+                // The receiver may already be gone if this client's task was
+                // cancelled/aborted without going through disconnect_client
+                // first. That's not this function's problem to solve, and
+                // must not panic here: it would poison `rooms` while still
+                // holding its lock, taking down every other room on the
+                // server.
+                let _ = client.waiting.take().unwrap().send(peer_contacts);
+            }
             rooms.remove(&room_id);
         }
 
@@ -126,4 +199,78 @@ impl State {
             rooms.remove(&room_id);
         });
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn room_fans_out_each_others_contacts_once_everyone_is_done() {
+        let mut state = State::default();
+        let room_id = state.create_room(2);
+
+        let creator_index = 0;
+        let peer_index = state.join_room(room_id).unwrap();
+        assert_eq!(peer_index, 1);
+
+        state
+            .update_client(room_id, creator_index, "127.0.0.1:1111".parse().unwrap(), false)
+            .unwrap();
+        state
+            .update_client(room_id, peer_index, "127.0.0.1:2222".parse().unwrap(), false)
+            .unwrap();
+
+        let creator_rx = state.set_client_done(room_id, creator_index).unwrap();
+        let peer_rx = state.set_client_done(room_id, peer_index).unwrap();
+
+        let creator_contacts = creator_rx.await.unwrap();
+        let peer_contacts = peer_rx.await.unwrap();
+
+        assert_eq!(creator_contacts.len(), 1);
+        assert_eq!(peer_contacts.len(), 1);
+        assert_eq!(creator_contacts[0].private.v4.unwrap().port(), 2222);
+        assert_eq!(peer_contacts[0].private.v4.unwrap().port(), 1111);
+    }
+
+    #[test]
+    fn disconnecting_before_the_room_fills_frees_the_slot() {
+        let mut state = State::default();
+        let room_id = state.create_room(2);
+
+        let first_peer = state.join_room(room_id).unwrap();
+        assert_eq!(first_peer, 1);
+
+        state.disconnect_client(room_id, first_peer);
+
+        let second_peer = state.join_room(room_id).unwrap();
+        assert_eq!(
+            second_peer, 1,
+            "the slot vacated by the disconnect should be reused, not appended"
+        );
+    }
+
+    #[test]
+    fn joining_a_full_room_is_rejected() {
+        let mut state = State::default();
+        let room_id = state.create_room(1);
+
+        assert!(matches!(state.join_room(room_id), Err(ServerError::RoomFull)));
+    }
+
+    #[tokio::test]
+    async fn a_dropped_receiver_does_not_poison_the_rest_of_the_fan_out() {
+        let mut state = State::default();
+        let room_id = state.create_room(2);
+        let peer_index = state.join_room(room_id).unwrap();
+
+        // Simulate the first client's task being cancelled after it calls
+        // set_client_done but before it awaits the receiver.
+        drop(state.set_client_done(room_id, 0).unwrap());
+
+        let peer_rx = state.set_client_done(room_id, peer_index).unwrap();
+        // Must not panic/poison `rooms`, even though client 0's receiver was
+        // already dropped: the peer still gets its fan-out.
+        assert!(peer_rx.await.is_ok());
+    }
+}
@@ -0,0 +1 @@
+pub mod encrypted_connection;
@@ -1,6 +1,8 @@
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(clippy::missing_errors_doc, clippy::must_use_candidate)]
 
+pub mod client;
+
 use postcard::{from_bytes, to_stdvec};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
@@ -22,21 +24,31 @@ pub enum Error {
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub enum ClientMessage {
-    /// Request the server to create a room
-    CreateRoom,
-    /// (password, user is creator of room?, private contact, done sending all info)
-    SendContact([u8; 6], bool, SocketAddr, bool),
+    /// Request the server to create a room for this many total
+    /// participants, including the creator
+    CreateRoom(u8),
+    /// Join an existing room by its password
+    JoinRoom([u8; 6]),
+    /// (this client's index within the room, private contact, done sending
+    /// all info)
+    SendContact(usize, SocketAddr, bool),
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub enum ServerMessage {
     /// Room successfully created
-    /// (room_password, user_id)
-    RoomCreated([u8; 6]),
-    /// (full contact info of peer)
-    SharePeerContacts(FullContact),
+    /// (room_password, this client's index within the room)
+    RoomCreated([u8; 6], usize),
+    /// Successfully joined an existing room
+    /// (this client's index within the room)
+    RoomJoined(usize),
+    /// Full contact info of every other participant in the room, sent once
+    /// all of them have signaled they're done
+    SharePeerContacts(Vec<FullContact>),
     SyntaxError,
     NoSuchRoomPasswordError,
+    /// The room already has as many participants as it was created for
+    RoomFullError,
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Default)]